@@ -0,0 +1,152 @@
+//! Online, page-by-page backup of a database into a second stable-memory
+//! region.
+//!
+//! [`Database::backup_step`] copies a single batch of pages and returns the
+//! running [`Progress`], the way `StableFile` already reads and writes
+//! pages positionally against its `VirtualMemory`. It's the resumable
+//! primitive: a caller with a large database spreads the copy across
+//! several canister messages (e.g. one batch per timer tick) by holding
+//! onto `Progress` and calling `backup_step` again with the next offset,
+//! instead of blowing the instruction budget of a single message.
+//! [`Database::backup`] is a convenience that drives `backup_step` to
+//! completion in one call, for databases known to fit comfortably within
+//! one message's budget; it does not yield between batches.
+
+use std::sync::atomic::Ordering;
+
+use ic_stable_structures::{memory_manager::VirtualMemory, Ic0StableMemory, Memory};
+
+use crate::{Database, Error, Result};
+
+const WASM_PAGE_BYTES: u64 = 65536;
+
+/// Progress of an in-flight backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Total number of pages in the source database.
+    pub pagecount: u32,
+    /// Number of pages copied so far, including the batch just finished.
+    pub copied: u32,
+    /// Number of pages left to copy.
+    pub remaining: u32,
+}
+
+impl Database {
+    /// Copy one batch of up to `step_pages` pages, starting at page offset
+    /// `copied`, from this database into `dst`, a second stable-memory
+    /// region.
+    ///
+    /// Returns the [`Progress`] after the batch; call again with
+    /// `progress.copied` as the next `copied` to resume. A caller can
+    /// return from the current canister message between calls (e.g. from a
+    /// timer callback) to spread a large backup across many messages.
+    ///
+    /// Returns an error if a transaction opened through the connection's
+    /// transaction manager ([`Connection::transaction`](crate::Connection::transaction),
+    /// [`Connection::with_transaction`](crate::Connection::with_transaction)
+    /// and the like) is open on any connection to the source database, as a
+    /// writer could otherwise mutate pages mid-copy. A transaction begun by
+    /// executing a raw `BEGIN` or `SAVEPOINT` statement, or by
+    /// [`Connection::savepoint`](crate::Connection::savepoint), is not
+    /// detected.
+    pub async fn backup_step(
+        &self,
+        dst: &VirtualMemory<Ic0StableMemory>,
+        copied: u32,
+        step_pages: u32,
+    ) -> Result<Progress> {
+        assert!(step_pages > 0, "step_pages must be greater than zero");
+
+        // `Connection::is_autocommit` only reflects the state of a single
+        // connection, so checking it on the fresh connection we're about
+        // to open below would always see autocommit, regardless of
+        // whether some *other* connection on this `Database` has a write
+        // transaction open. `open_transactions` is shared across every
+        // connection returned by `Database::connect`, so this catches a
+        // transaction that another connection opened through its
+        // transaction manager.
+        if self.open_transactions.load(Ordering::SeqCst) > 0 {
+            return Err(Error::SqlExecutionFailure(
+                "cannot back up while a transaction is open on the source database".to_string(),
+            ));
+        }
+
+        let conn = self.connect().await?;
+
+        // `src.read` below reads raw bytes straight out of stable memory,
+        // bypassing whatever pages `turso_core` is still holding dirty in
+        // its own in-memory page cache. Flush those through first so the
+        // batch we're about to copy reflects what's actually been written
+        // so far, not a stale on-disk image.
+        conn.cacheflush()?;
+
+        let page_size = pragma_u32(&conn, "page_size")?;
+        let pagecount = pragma_u32(&conn, "page_count")?;
+
+        let src = self.virtual_memory.clone();
+        let batch_pages = step_pages.min(pagecount.saturating_sub(copied));
+        let offset = u64::from(copied) * u64::from(page_size);
+        let len = (batch_pages as usize) * (page_size as usize);
+
+        let mut buf = vec![0u8; len];
+        src.read(offset, &mut buf);
+        grow_to_fit(dst, offset, buf.len())?;
+        // `VirtualMemory::write` completes synchronously and durably on the
+        // IC, so there's no separate "finalize by syncing" step needed for
+        // `dst` once this returns.
+        dst.write(offset, &buf);
+
+        let copied = copied + batch_pages;
+        Ok(Progress {
+            pagecount,
+            copied,
+            remaining: pagecount - copied,
+        })
+    }
+
+    /// Copy this database into `dst`, a second stable-memory region, one
+    /// batch of `step_pages` pages at a time, in a single call.
+    ///
+    /// `progress` is called after each batch with the running [`Progress`].
+    /// For a database too large to copy within one message's instruction
+    /// budget, call [`Database::backup_step`] directly instead and drive it
+    /// across several messages.
+    pub async fn backup(
+        &self,
+        dst: VirtualMemory<Ic0StableMemory>,
+        step_pages: u32,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<()> {
+        let mut copied = 0u32;
+        loop {
+            let p = self.backup_step(&dst, copied, step_pages).await?;
+            copied = p.copied;
+            progress(p);
+            if p.remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn grow_to_fit(memory: &VirtualMemory<Ic0StableMemory>, offset: u64, len: usize) -> Result<()> {
+    let required_pages = (offset + len as u64).div_ceil(WASM_PAGE_BYTES);
+    let current_pages = memory.size();
+    if required_pages > current_pages {
+        if memory.grow(required_pages - current_pages) == -1 {
+            return Err(Error::SqlExecutionFailure(
+                "could not grow destination memory".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn pragma_u32(conn: &crate::Connection, name: &str) -> Result<u32> {
+    let mut value = 0u32;
+    conn.pragma_query(name, |row| {
+        value = *row.get_value(0).unwrap().as_integer().unwrap() as u32;
+        Ok(())
+    })?;
+    Ok(value)
+}
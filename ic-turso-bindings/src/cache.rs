@@ -0,0 +1,67 @@
+//! An LRU cache of idle prepared statements, keyed by the SQL text used to
+//! prepare them.
+//!
+//! Re-parsing and re-planning a SQL string on every call is expensive inside
+//! a canister, where every instruction costs cycles. [`StatementCache`] lets
+//! [`Connection::prepare_cached`](crate::Connection::prepare_cached) hand back a
+//! previously-prepared `turso_core::Statement` instead of asking
+//! `turso_core` to compile the SQL again, provided the exact same SQL text
+//! was used before and the statement isn't already checked out elsewhere.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Default number of idle statements kept around per [`Connection`](crate::Connection).
+pub const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 16;
+
+struct CacheEntry {
+    sql: String,
+    stmt: Arc<Mutex<turso_core::Statement>>,
+}
+
+pub(crate) struct StatementCache {
+    capacity: usize,
+    // Least-recently-used entries sit at the front; `push` appends, `pop`
+    // removes wherever the match is found.
+    entries: VecDeque<CacheEntry>,
+}
+
+impl StatementCache {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_to_capacity();
+    }
+
+    /// Remove and return an idle statement matching `sql`, if one is cached.
+    pub(crate) fn pop(&mut self, sql: &str) -> Option<Arc<Mutex<turso_core::Statement>>> {
+        let idx = self.entries.iter().position(|e| e.sql == sql)?;
+        Some(self.entries.remove(idx).unwrap().stmt)
+    }
+
+    /// Return a checked-out statement to the pool, evicting the
+    /// least-recently-used entry if the cache is over capacity.
+    pub(crate) fn push(&mut self, sql: String, stmt: Arc<Mutex<turso_core::Statement>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.push_back(CacheEntry { sql, stmt });
+        self.evict_to_capacity();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict_to_capacity(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! User-defined SQL functions.
+//!
+//! Wires Rust closures into `turso_core`'s function registry, the same
+//! surface rusqlite exposes via `create_scalar_function`/
+//! `create_aggregate_function`. Beyond generic UDFs this lets application
+//! SQL reach into the canister's execution context directly, without a
+//! round-trip through Rust: [`Connection::register_ic_functions`] installs
+//! `ic_caller()`, `ic_time()`, and `principal_blob(text)`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{Connection, Error, Result, Value};
+
+/// The arguments passed to a user-defined function for one invocation.
+pub struct Context<'a> {
+    args: &'a [turso_core::Value],
+}
+
+impl Context<'_> {
+    /// Number of arguments passed to this invocation.
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Fetch and convert the argument at `index`.
+    pub fn get(&self, index: usize) -> Result<Value> {
+        let value = self.args.get(index).ok_or_else(|| {
+            Error::SqlExecutionFailure(format!("argument index {index} out of range"))
+        })?;
+        Ok(match value {
+            turso_core::Value::Integer(i) => Value::Integer(*i),
+            turso_core::Value::Null => Value::Null,
+            turso_core::Value::Float(f) => Value::Real(*f),
+            turso_core::Value::Text(text) => Value::Text(text.to_string()),
+            turso_core::Value::Blob(items) => Value::Blob(items.to_vec()),
+        })
+    }
+}
+
+/// Whether a function is guaranteed to return the same result for the same
+/// arguments within one statement execution.
+///
+/// On the IC this matters beyond query planning: a non-deterministic
+/// built-in could make a replica diverge from its peers, so every function
+/// registered via this module must document which it is.
+pub type Deterministic = bool;
+
+/// The three phases of a user-defined aggregate function.
+pub trait Aggregate: Send + Sync + 'static {
+    /// Per-group accumulator.
+    type State: Default;
+
+    /// Fold one row's arguments into `state`.
+    fn step(&self, ctx: &Context, state: &mut Self::State) -> Result<()>;
+
+    /// Produce the final result for a group. `state` is `None` if the group
+    /// had no rows.
+    fn finalize(&self, state: Option<Self::State>) -> Result<Value>;
+}
+
+impl Connection {
+    /// Register a scalar SQL function backed by a Rust closure.
+    ///
+    /// `n_args` is the number of arguments the function accepts, or `-1` for
+    /// a variadic function. Set `deterministic` only if the function returns
+    /// the same output for the same input within one statement execution
+    /// (see [`Deterministic`]) and has no side effects — the IC requires
+    /// reproducible execution across replicas, so a non-deterministic
+    /// function must never be flagged as one.
+    pub fn create_scalar_function<F>(
+        &self,
+        name: &str,
+        n_args: i32,
+        deterministic: Deterministic,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(&Context) -> Result<Value> + Send + Sync + 'static,
+    {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        conn.create_scalar_function(
+            name,
+            n_args,
+            deterministic,
+            move |args: &[turso_core::Value]| {
+                let ctx = Context { args };
+                func(&ctx)
+                    .map(turso_core::Value::from)
+                    .map_err(|e| turso_core::LimboError::InternalError(e.to_string()))
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Register an aggregate SQL function backed by an [`Aggregate`] impl.
+    ///
+    /// `turso_core` hands step/finalize an `agg_id` identifying which
+    /// aggregate instance a call belongs to — a distinct id per occurrence
+    /// of the function in the query (e.g. the two calls in
+    /// `SELECT my_agg(a), my_agg(b) FROM t`) and per GROUP BY group within
+    /// an occurrence. Keying accumulator storage off it, instead of one
+    /// `Arc<RefCell<..>>` fixed at registration, keeps concurrent
+    /// occurrences' state from clobbering each other.
+    pub fn create_aggregate_function<A: Aggregate>(
+        &self,
+        name: &str,
+        n_args: i32,
+        aggregate: A,
+    ) -> Result<()> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        let aggregate = std::sync::Arc::new(aggregate);
+        let states: std::sync::Arc<Mutex<HashMap<u64, A::State>>> =
+            std::sync::Arc::new(Mutex::new(HashMap::new()));
+
+        let step_aggregate = std::sync::Arc::clone(&aggregate);
+        let step_states = std::sync::Arc::clone(&states);
+        let finalize_aggregate = aggregate;
+        let finalize_states = states;
+
+        conn.create_aggregate_function(
+            name,
+            n_args,
+            move |agg_id: u64, args: &[turso_core::Value]| {
+                let ctx = Context { args };
+                let mut states = step_states
+                    .lock()
+                    .map_err(|e| turso_core::LimboError::InternalError(e.to_string()))?;
+                let state = states.entry(agg_id).or_default();
+                step_aggregate
+                    .step(&ctx, state)
+                    .map_err(|e| turso_core::LimboError::InternalError(e.to_string()))
+            },
+            move |agg_id: u64| {
+                let state = finalize_states
+                    .lock()
+                    .map_err(|e| turso_core::LimboError::InternalError(e.to_string()))?
+                    .remove(&agg_id);
+                finalize_aggregate
+                    .finalize(state)
+                    .map(turso_core::Value::from)
+                    .map_err(|e| turso_core::LimboError::InternalError(e.to_string()))
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Register the canister-native built-ins: `ic_caller()`, `ic_time()`,
+    /// and `principal_blob(text)`.
+    ///
+    /// `ic_caller()` and `ic_time()` take no arguments and return the
+    /// calling principal and the ingress timestamp of the current update or
+    /// query call; both are reproducible across replicas for a given call,
+    /// so they are registered as deterministic. `principal_blob(text)`
+    /// converts a principal's textual representation into the raw bytes
+    /// SQLite stores as a `BLOB`, for compact indexing and comparison.
+    pub fn register_ic_functions(&self) -> Result<()> {
+        self.create_scalar_function("ic_caller", 0, true, |_ctx| {
+            Ok(Value::Text(ic_cdk::api::caller().to_text()))
+        })?;
+
+        self.create_scalar_function("ic_time", 0, true, |_ctx| {
+            Ok(Value::Integer(ic_cdk::api::time() as i64))
+        })?;
+
+        self.create_scalar_function("principal_blob", 1, true, |ctx| {
+            let text = ctx.get(0)?;
+            let text = text.as_text().ok_or_else(|| {
+                Error::SqlExecutionFailure("principal_blob: expected TEXT argument".to_string())
+            })?;
+            let principal = candid::Principal::from_text(text)
+                .map_err(|e| Error::SqlExecutionFailure(format!("principal_blob: {e}")))?;
+            Ok(Value::Blob(principal.as_slice().to_vec()))
+        })?;
+
+        Ok(())
+    }
+}
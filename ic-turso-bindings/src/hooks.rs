@@ -0,0 +1,148 @@
+//! Commit, rollback, and row-update hooks.
+//!
+//! These mirror rusqlite's `hooks` module: a canister can use them to
+//! maintain an append-only audit trail, invalidate the prepared-statement
+//! cache on write, or trigger a `cacheflush` at transaction boundaries.
+//! Hooks are stored per-[`Connection`], behind the same `Arc<Mutex<..>>`
+//! the rest of the connection state uses, and are cleared by passing `None`.
+
+use std::sync::{Arc, Mutex};
+
+use crate::{Connection, Error, Result};
+
+/// The kind of row-level change an update hook fires for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl From<turso_core::Action> for Action {
+    fn from(action: turso_core::Action) -> Self {
+        match action {
+            turso_core::Action::Insert => Action::Insert,
+            turso_core::Action::Update => Action::Update,
+            turso_core::Action::Delete => Action::Delete,
+        }
+    }
+}
+
+type UpdateHook = Box<dyn FnMut(Action, &str, i64) + Send + 'static>;
+type CommitHook = Box<dyn FnMut() -> bool + Send + 'static>;
+type RollbackHook = Box<dyn FnMut() + Send + 'static>;
+
+pub(crate) struct Hooks {
+    pub(crate) update: Arc<Mutex<Option<UpdateHook>>>,
+    pub(crate) commit: Arc<Mutex<Option<CommitHook>>>,
+    pub(crate) rollback: Arc<Mutex<Option<RollbackHook>>>,
+}
+
+impl Hooks {
+    pub(crate) fn new() -> Self {
+        #[allow(clippy::arc_with_non_send_sync)]
+        Self {
+            update: Arc::new(Mutex::new(None)),
+            commit: Arc::new(Mutex::new(None)),
+            rollback: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl Connection {
+    /// Set (or, with `None`, clear) the hook fired after every successful
+    /// INSERT, UPDATE or DELETE, with the operation kind, the affected
+    /// table name, and the affected rowid.
+    pub fn set_update_hook<F>(&self, hook: Option<F>) -> Result<()>
+    where
+        F: FnMut(Action, &str, i64) + Send + 'static,
+    {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        let slot = Arc::clone(&self.hooks.update);
+        match hook {
+            Some(mut hook) => {
+                *slot.lock().unwrap() = Some(Box::new(move |action, table, rowid| {
+                    hook(action, table, rowid)
+                }));
+                let callback_slot = Arc::clone(&slot);
+                conn.set_update_hook(Some(Box::new(move |action, table, rowid| {
+                    if let Some(hook) = callback_slot.lock().unwrap().as_mut() {
+                        hook(action.into(), table, rowid);
+                    }
+                })));
+            }
+            None => {
+                *slot.lock().unwrap() = None;
+                conn.set_update_hook(None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set (or, with `None`, clear) the hook fired at the start of a commit.
+    /// Returning `false` vetoes the commit, turning it into a rollback.
+    pub fn set_commit_hook<F>(&self, hook: Option<F>) -> Result<()>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        let slot = Arc::clone(&self.hooks.commit);
+        match hook {
+            Some(hook) => {
+                *slot.lock().unwrap() = Some(Box::new(hook));
+                let callback_slot = Arc::clone(&slot);
+                conn.set_commit_hook(Some(Box::new(move || {
+                    callback_slot
+                        .lock()
+                        .unwrap()
+                        .as_mut()
+                        .map(|hook| hook())
+                        .unwrap_or(true)
+                })));
+            }
+            None => {
+                *slot.lock().unwrap() = None;
+                conn.set_commit_hook(None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set (or, with `None`, clear) the hook fired whenever a transaction is
+    /// rolled back.
+    pub fn set_rollback_hook<F>(&self, hook: Option<F>) -> Result<()>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        let slot = Arc::clone(&self.hooks.rollback);
+        match hook {
+            Some(hook) => {
+                *slot.lock().unwrap() = Some(Box::new(hook));
+                let callback_slot = Arc::clone(&slot);
+                conn.set_rollback_hook(Some(Box::new(move || {
+                    if let Some(hook) = callback_slot.lock().unwrap().as_mut() {
+                        hook();
+                    }
+                })));
+            }
+            None => {
+                *slot.lock().unwrap() = None;
+                conn.set_rollback_hook(None);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! A durable background job queue, backed by a table in the same database
+//! as the rest of the canister's data.
+//!
+//! Queries and canister timers only live as long as the call or the
+//! in-memory timer registration that issued them — a queued job recorded
+//! as a plain `Vec` would vanish on the next `pre_upgrade`. Storing the
+//! queue as a table means it rides along with everything else already
+//! persisted in stable memory and survives an upgrade for free.
+//!
+//! [`Connection::fetch_and_touch_task`] claims the next due task inside an
+//! `IMMEDIATE` transaction, so two overlapping timer callbacks (or a timer
+//! callback racing a canister call on the same connection) can never claim
+//! the same row. [`Connection::spawn_runner`] drives one job type with
+//! [`ic_cdk_timers`], the same way the demo canister's `init_timer` seeds
+//! its RNG: a recurring [`ic_cdk_timers::set_timer_interval`], firing every
+//! `poll_interval`, that spawns an async poll on every tick.
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+use ic_cdk_timers::TimerId;
+
+use crate::transaction::TransactionBehavior;
+use crate::{Connection, Result};
+
+/// A job type a [`Connection`] can run from the queue.
+///
+/// Implementors round-trip through [`to_payload`](Runnable::to_payload) and
+/// [`from_payload`](Runnable::from_payload) so the queue table only ever
+/// needs to store opaque bytes.
+pub trait Runnable: Sized + Send + 'static {
+    /// The `tasks.task_type` value this job is queued and claimed under.
+    fn task_type() -> &'static str;
+
+    /// Encode this job for storage in `tasks.payload`.
+    fn to_payload(&self) -> Vec<u8>;
+
+    /// Decode a job previously encoded with
+    /// [`to_payload`](Runnable::to_payload).
+    fn from_payload(payload: &[u8]) -> std::result::Result<Self, BoxError>;
+
+    /// Run the job against the connection it was claimed from.
+    fn run<'a>(
+        &'a self,
+        conn: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<(), BoxError>> + 'a>>;
+}
+
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A task claimed by [`Connection::fetch_and_touch_task`], ready to be
+/// decoded with [`Runnable::from_payload`] and run.
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub id: i64,
+    pub payload: Vec<u8>,
+    pub retries: i64,
+    pub backoff_secs: i64,
+}
+
+const CREATE_TASKS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS tasks (
+    id INTEGER PRIMARY KEY,
+    task_type TEXT NOT NULL,
+    payload BLOB NOT NULL,
+    state TEXT NOT NULL DEFAULT 'pending',
+    run_at INTEGER NOT NULL,
+    retries INTEGER NOT NULL DEFAULT 0,
+    max_retries INTEGER NOT NULL DEFAULT 5,
+    backoff_secs INTEGER NOT NULL DEFAULT 1,
+    period_secs INTEGER
+)";
+
+impl Connection {
+    /// Create the `tasks` table if it doesn't already exist.
+    ///
+    /// Safe to call on every canister `init`/`post_upgrade`, the same way
+    /// the rest of the demo's `CREATE TABLE IF NOT EXISTS` calls are.
+    pub async fn init_tasks_table(&self) -> Result<()> {
+        self.execute(CREATE_TASKS_TABLE, ()).await?;
+        Ok(())
+    }
+
+    /// Queue a one-shot job, due at `run_at_ns` (nanoseconds since epoch,
+    /// e.g. from `ic_cdk::api::time()`). Returns the new row's id.
+    pub async fn insert_task(&self, task_type: &str, payload: &[u8], run_at_ns: i64) -> Result<i64> {
+        self.schedule_task(task_type, payload, run_at_ns, None).await
+    }
+
+    /// Queue a job that reschedules itself every `period_secs` seconds after
+    /// [`finish_task`](Connection::finish_task) instead of being removed
+    /// from the queue.
+    pub async fn schedule_periodic_task(
+        &self,
+        task_type: &str,
+        payload: &[u8],
+        run_at_ns: i64,
+        period_secs: i64,
+    ) -> Result<i64> {
+        self.schedule_task(task_type, payload, run_at_ns, Some(period_secs))
+            .await
+    }
+
+    async fn schedule_task(
+        &self,
+        task_type: &str,
+        payload: &[u8],
+        run_at_ns: i64,
+        period_secs: Option<i64>,
+    ) -> Result<i64> {
+        let mut rows = self
+            .query(
+                "INSERT INTO tasks (task_type, payload, run_at, period_secs)
+                 VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                (task_type.to_string(), payload.to_vec(), run_at_ns, period_secs),
+            )
+            .await?;
+        let row = rows
+            .next()
+            .await?
+            .expect("INSERT ... RETURNING id always yields exactly one row");
+        Ok(*row
+            .get_value(0)?
+            .as_integer()
+            .expect("tasks.id is an INTEGER column"))
+    }
+
+    /// Atomically claim the next due `task_type` task, marking it `running`
+    /// so a second caller can't claim it too.
+    ///
+    /// Opened through the same depth-aware transaction manager as
+    /// [`Connection::transaction`]/[`Connection::with_transaction`], with
+    /// `IMMEDIATE` behavior: at depth 1 that takes the write lock up front,
+    /// so the select-then-update can't interleave with another
+    /// `fetch_and_touch_task` call the way `DEFERRED` would allow. Calling
+    /// this from inside an already-open transaction composes into a nested
+    /// `SAVEPOINT` instead of emitting a second, rejected `BEGIN`.
+    pub async fn fetch_and_touch_task(&self, task_type: &str, now_ns: i64) -> Result<Option<Task>> {
+        let depth = self.begin_transaction(TransactionBehavior::Immediate).await?;
+
+        let claimed = self.claim_next_due_task(task_type, now_ns).await;
+
+        match claimed {
+            Ok(task) => {
+                self.commit_transaction(depth).await?;
+                Ok(task)
+            }
+            Err(err) => {
+                let _ = self.rollback_transaction(depth).await;
+                Err(err)
+            }
+        }
+    }
+
+    async fn claim_next_due_task(&self, task_type: &str, now_ns: i64) -> Result<Option<Task>> {
+        let mut stmt = self
+            .prepare_cached(
+                "SELECT id, payload, retries, backoff_secs FROM tasks
+                 WHERE task_type = ?1 AND state = 'pending' AND run_at <= ?2
+                 ORDER BY run_at ASC LIMIT 1",
+            )
+            .await?;
+        let mut rows = stmt.query((task_type.to_string(), now_ns)).await?;
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+
+        let task = Task {
+            id: *row
+                .get_value(0)?
+                .as_integer()
+                .expect("tasks.id is an INTEGER column"),
+            payload: row
+                .get_value(1)?
+                .as_blob()
+                .expect("tasks.payload is a BLOB column")
+                .to_vec(),
+            retries: *row
+                .get_value(2)?
+                .as_integer()
+                .expect("tasks.retries is an INTEGER column"),
+            backoff_secs: *row
+                .get_value(3)?
+                .as_integer()
+                .expect("tasks.backoff_secs is an INTEGER column"),
+        };
+        drop(rows);
+
+        self.execute("UPDATE tasks SET state = 'running' WHERE id = ?1", [task.id])
+            .await?;
+        Ok(Some(task))
+    }
+
+    /// Mark `id` as done. A periodic task is reset to `pending` with
+    /// `run_at` pushed `period_secs` into the future from `now_ns`; a
+    /// one-shot task is deleted from the queue.
+    pub async fn finish_task(&self, id: i64, now_ns: i64) -> Result<()> {
+        self.execute(
+            "UPDATE tasks
+             SET state = 'pending', run_at = ?2 + period_secs * 1000000000
+             WHERE id = ?1 AND period_secs IS NOT NULL",
+            (id, now_ns),
+        )
+        .await?;
+        self.execute("DELETE FROM tasks WHERE id = ?1 AND period_secs IS NULL", [id])
+            .await?;
+        Ok(())
+    }
+
+    /// Return a failed task to `pending` with an exponentially growing
+    /// delay (`backoff_secs * 2^retries`), or mark it `failed` once
+    /// `max_retries` is exceeded.
+    pub async fn retry_task(&self, id: i64, now_ns: i64) -> Result<()> {
+        self.execute(
+            "UPDATE tasks
+             SET state = 'failed'
+             WHERE id = ?1 AND retries >= max_retries",
+            [id],
+        )
+        .await?;
+        self.execute(
+            "UPDATE tasks
+             SET state = 'pending',
+                 retries = retries + 1,
+                 run_at = ?2 + (backoff_secs * (1 << retries)) * 1000000000
+             WHERE id = ?1 AND retries < max_retries",
+            (id, now_ns),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Poll for due `R` tasks every `poll_interval`, running each one and
+    /// calling [`finish_task`](Connection::finish_task) or
+    /// [`retry_task`](Connection::retry_task) depending on the outcome.
+    ///
+    /// Mirrors the demo canister's `init_timer`: a recurring
+    /// [`ic_cdk_timers`] timer that spawns an async block on every tick.
+    /// Call it once per job type you want to drive; the returned
+    /// [`TimerId`] can be passed to [`ic_cdk_timers::clear_timer`] to stop
+    /// polling.
+    pub fn spawn_runner<R: Runnable>(&self, poll_interval: Duration) -> TimerId {
+        let conn = self.clone();
+        ic_cdk_timers::set_timer_interval(poll_interval, move || {
+            let conn = conn.clone();
+            ic_cdk::futures::spawn(async move {
+                if let Err(err) = conn.run_next_due::<R>().await {
+                    ic_cdk::println!("job queue: {} task failed: {err}", R::task_type());
+                }
+            });
+        })
+    }
+
+    async fn run_next_due<R: Runnable>(&self) -> std::result::Result<(), BoxError> {
+        let now_ns = ic_cdk::api::time() as i64;
+        let Some(task) = self
+            .fetch_and_touch_task(R::task_type(), now_ns)
+            .await
+            .map_err(|e| Box::new(e) as BoxError)?
+        else {
+            return Ok(());
+        };
+
+        let result = match R::from_payload(&task.payload) {
+            Ok(job) => job.run(self).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => self.finish_task(task.id, now_ns).await,
+            Err(_) => self.retry_task(task.id, now_ns).await,
+        }
+        .map_err(|e| Box::new(e) as BoxError)
+    }
+}
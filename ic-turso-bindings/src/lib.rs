@@ -11,7 +11,7 @@
 //! use turso::Builder;
 //!
 //! let db = Builder::new_local(":memory:").build().await.unwrap();
-//! let conn = db.connect().unwrap();
+//! let conn = db.connect().await.unwrap();
 //! conn.execute("CREATE TABLE IF NOT EXISTS users (email TEXT)", ()).await.unwrap();
 //! conn.execute("INSERT INTO users (email) VALUES ('alice@example.org')", ()).await.unwrap();
 //! # }
@@ -32,7 +32,14 @@
 //! # }
 //! ```
 
+pub mod backup;
+pub mod blob;
+mod cache;
+pub mod function;
+pub mod hooks;
+pub mod jobs;
 pub mod params;
+pub mod session;
 pub mod stable_io;
 pub mod transaction;
 pub mod value;
@@ -42,12 +49,20 @@ use transaction::TransactionBehavior;
 use turso_core::OpenFlags;
 pub use value::Value;
 
+pub use backup::Progress;
+pub use blob::Blob;
+pub use function::{Aggregate, Context};
+pub use hooks::Action;
+pub use jobs::{Runnable, Task};
 pub use params::params_from_iter;
+pub use session::{ChangesetOp, ConflictKind, ConflictResolution, Session};
 
+use crate::cache::{StatementCache, DEFAULT_STATEMENT_CACHE_CAPACITY};
 use crate::params::*;
 use crate::stable_io::{StableDatabaseStorage, StableIO};
 use std::fmt::Debug;
 use std::num::NonZero;
+use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug, thiserror::Error)]
@@ -73,23 +88,39 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// A builder for `Database`.
 pub struct Builder {
     virtual_memory: VirtualMemory<Ic0StableMemory>,
+    wal_memory: VirtualMemory<Ic0StableMemory>,
 }
 
 impl Builder {
-    /// Create a new local database.
-    pub fn with_memory(virtual_memory: VirtualMemory<Ic0StableMemory>) -> Self {
-        Self { virtual_memory }
+    /// Create a new local database backed by `virtual_memory`, with its
+    /// write-ahead log backed by the dedicated `wal_memory` region so WAL
+    /// pages survive a trap or an upgrade rather than living only in heap
+    /// memory.
+    pub fn with_memory(
+        virtual_memory: VirtualMemory<Ic0StableMemory>,
+        wal_memory: VirtualMemory<Ic0StableMemory>,
+    ) -> Self {
+        Self {
+            virtual_memory,
+            wal_memory,
+        }
     }
 
     /// Build the database.
     #[allow(unused_variables, clippy::arc_with_non_send_sync)]
     pub async fn build(self) -> Result<Database> {
         let path = "db";
-        let io: Arc<dyn turso_core::IO> = Arc::new(StableIO::new(self.virtual_memory));
+        let virtual_memory = self.virtual_memory.clone();
+        let io: Arc<dyn turso_core::IO> =
+            Arc::new(StableIO::new(self.virtual_memory, self.wal_memory));
         let file = io.open_file(path, OpenFlags::Create, false).unwrap();
         let db_file = Arc::new(StableDatabaseStorage::new(file));
         let db = turso_core::Database::open(io, path, db_file, false, true).unwrap();
-        Ok(Database { inner: db })
+        Ok(Database {
+            inner: db,
+            virtual_memory,
+            open_transactions: Arc::new(AtomicU32::new(0)),
+        })
     }
 }
 
@@ -99,6 +130,14 @@ impl Builder {
 #[derive(Clone)]
 pub struct Database {
     inner: Arc<turso_core::Database>,
+    pub(crate) virtual_memory: VirtualMemory<Ic0StableMemory>,
+    // Number of connections on this `Database` with a top-level transaction
+    // currently open. `Connection::is_autocommit` only reflects a single
+    // connection's own state, which is useless for guarding against a
+    // *different* connection's writer — this is shared across every
+    // `Connection` returned by `connect`, via `Database::backup_step`'s
+    // guard, so it answers "is anyone writing" instead.
+    pub(crate) open_transactions: Arc<AtomicU32>,
 }
 
 unsafe impl Send for Database {}
@@ -110,15 +149,35 @@ impl Debug for Database {
     }
 }
 
+/// Pragmas run on every new connection, mirroring Zed's
+/// `CONNECTION_INITIALIZE_QUERY`. `journal_mode = WAL` keeps readers from
+/// blocking on writers, and `synchronous = NORMAL` is the recommended
+/// pairing for WAL mode.
+const CONNECTION_INITIALIZE_QUERY: &[&str] =
+    &["PRAGMA journal_mode = WAL", "PRAGMA synchronous = NORMAL"];
+
 impl Database {
     /// Connect to the database.
-    pub fn connect(&self) -> Result<Connection> {
+    pub async fn connect(&self) -> Result<Connection> {
         let conn = self.inner.connect()?;
         #[allow(clippy::arc_with_non_send_sync)]
         let connection = Connection {
             inner: Arc::new(Mutex::new(conn)),
             transaction_behavior: TransactionBehavior::Deferred,
+            #[allow(clippy::arc_with_non_send_sync)]
+            cache: Arc::new(Mutex::new(StatementCache::with_capacity(
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+            ))),
+            hooks: Arc::new(crate::hooks::Hooks::new()),
+            savepoint_depth: Arc::new(Mutex::new(0)),
+            tx_manager: Arc::new(Mutex::new(transaction::TransactionManager::new())),
+            open_transactions: Arc::clone(&self.open_transactions),
         };
+
+        for pragma in CONNECTION_INITIALIZE_QUERY {
+            connection.execute(pragma, ()).await?;
+        }
+
         Ok(connection)
     }
 }
@@ -127,6 +186,11 @@ impl Database {
 pub struct Connection {
     inner: Arc<Mutex<Arc<turso_core::Connection>>>,
     transaction_behavior: TransactionBehavior,
+    cache: Arc<Mutex<StatementCache>>,
+    hooks: Arc<crate::hooks::Hooks>,
+    savepoint_depth: Arc<Mutex<u32>>,
+    tx_manager: Arc<Mutex<transaction::TransactionManager>>,
+    pub(crate) open_transactions: Arc<AtomicU32>,
 }
 
 impl Clone for Connection {
@@ -134,6 +198,11 @@ impl Clone for Connection {
         Self {
             inner: Arc::clone(&self.inner),
             transaction_behavior: self.transaction_behavior,
+            cache: Arc::clone(&self.cache),
+            hooks: Arc::clone(&self.hooks),
+            savepoint_depth: Arc::clone(&self.savepoint_depth),
+            tx_manager: Arc::clone(&self.tx_manager),
+            open_transactions: Arc::clone(&self.open_transactions),
         }
     }
 }
@@ -143,18 +212,33 @@ unsafe impl Sync for Connection {}
 
 impl Connection {
     /// Query the database with SQL.
+    ///
+    /// Goes through [`prepare_cached`](Connection::prepare_cached) rather
+    /// than [`prepare`](Connection::prepare), so repeated calls with the
+    /// same SQL text reuse a pooled statement instead of asking
+    /// `turso_core` to parse and plan it again.
     pub async fn query(&self, sql: &str, params: impl IntoParams) -> Result<Rows> {
-        let mut stmt = self.prepare(sql).await?;
+        let mut stmt = self.prepare_cached(sql).await?;
         stmt.query(params).await
     }
 
     /// Execute SQL statement on the database.
+    ///
+    /// Goes through [`prepare_cached`](Connection::prepare_cached) rather
+    /// than [`prepare`](Connection::prepare), so repeated calls with the
+    /// same SQL text reuse a pooled statement instead of asking
+    /// `turso_core` to parse and plan it again.
     pub async fn execute(&self, sql: &str, params: impl IntoParams) -> Result<u64> {
-        let mut stmt = self.prepare(sql).await?;
+        let mut stmt = self.prepare_cached(sql).await?;
         stmt.execute(params).await
     }
 
     /// Prepare a SQL statement for later execution.
+    ///
+    /// Every call asks `turso_core` to parse and plan `sql` from scratch. On
+    /// the Internet Computer, where every instruction costs cycles, prefer
+    /// [`prepare_cached`](Connection::prepare_cached) for statements that
+    /// are executed repeatedly with the same SQL text.
     pub async fn prepare(&self, sql: &str) -> Result<Statement> {
         let conn = self
             .inner
@@ -166,10 +250,63 @@ impl Connection {
         #[allow(clippy::arc_with_non_send_sync)]
         let statement = Statement {
             inner: Arc::new(Mutex::new(stmt)),
+            sql: sql.to_string(),
+            cache: None,
         };
         Ok(statement)
     }
 
+    /// Prepare a SQL statement, reusing an idle statement from the
+    /// connection's prepared-statement cache when one was parsed from the
+    /// exact same SQL text.
+    ///
+    /// If a match is found, it is returned after a `reset()` that clears any
+    /// parameter bindings left over from its previous use, instead of asking
+    /// `turso_core` to parse and plan the SQL again. Either way, the
+    /// returned [`Statement`] is placed back into the cache when it is
+    /// dropped, so callers don't need to do anything special to benefit
+    /// beyond calling `prepare_cached` instead of `prepare`.
+    pub async fn prepare_cached(&self, sql: &str) -> Result<Statement> {
+        if let Some(stmt) = self.cache.lock().unwrap().pop(sql) {
+            stmt.lock().unwrap().reset();
+            return Ok(Statement {
+                inner: stmt,
+                sql: sql.to_string(),
+                cache: Some(Arc::clone(&self.cache)),
+            });
+        }
+
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        let stmt = conn.prepare(sql)?;
+
+        #[allow(clippy::arc_with_non_send_sync)]
+        let statement = Statement {
+            inner: Arc::new(Mutex::new(stmt)),
+            sql: sql.to_string(),
+            cache: Some(Arc::clone(&self.cache)),
+        };
+        Ok(statement)
+    }
+
+    /// Set the capacity of the prepared-statement cache used by
+    /// [`prepare_cached`](Connection::prepare_cached).
+    ///
+    /// Lowering the capacity evicts the least-recently-used idle statements
+    /// immediately. Defaults to 16 idle statements.
+    pub fn set_prepared_statement_cache_capacity(&self, capacity: usize) {
+        self.cache.lock().unwrap().set_capacity(capacity);
+    }
+
+    /// Discard every idle statement currently held in the prepared-statement
+    /// cache.
+    pub fn clear_prepared_statement_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
     /// Query a pragma.
     pub fn pragma_query<F>(&self, pragma_name: &str, mut f: F) -> Result<()>
     where
@@ -214,6 +351,36 @@ impl Connection {
 
         Ok(conn.get_auto_commit())
     }
+
+    /// Number of rows inserted, updated, or deleted by the most recently
+    /// completed INSERT, UPDATE, or DELETE statement on this connection.
+    ///
+    /// Unlike [`Statement::execute`]'s return value (a `StepResult` status
+    /// code, not a row count), this mirrors `sqlite3_changes()` and is the
+    /// right thing to check to tell "zero rows matched" apart from "the
+    /// statement finished".
+    pub fn changes(&self) -> Result<u64> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        Ok(conn.changes())
+    }
+
+    /// Increment and return the savepoint nesting depth, used to name
+    /// unnamed savepoints `_sp_<depth>`.
+    pub(crate) fn next_savepoint_depth(&self) -> u32 {
+        let mut depth = self.savepoint_depth.lock().unwrap();
+        *depth += 1;
+        *depth
+    }
+
+    /// Pop one level of savepoint nesting once a savepoint is released.
+    pub(crate) fn release_savepoint_depth(&self) {
+        let mut depth = self.savepoint_depth.lock().unwrap();
+        *depth = depth.saturating_sub(1);
+    }
 }
 
 impl Debug for Connection {
@@ -225,12 +392,16 @@ impl Debug for Connection {
 /// A prepared statement.
 pub struct Statement {
     inner: Arc<Mutex<turso_core::Statement>>,
+    sql: String,
+    cache: Option<Arc<Mutex<StatementCache>>>,
 }
 
 impl Clone for Statement {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            sql: self.sql.clone(),
+            cache: self.cache.clone(),
         }
     }
 }
@@ -238,6 +409,28 @@ impl Clone for Statement {
 unsafe impl Send for Statement {}
 unsafe impl Sync for Statement {}
 
+impl Drop for Statement {
+    fn drop(&mut self) {
+        return_to_cache(&self.cache, &self.sql, &self.inner);
+    }
+}
+
+/// Return `stmt` to `cache` if this is the last outstanding handle to it
+/// (i.e. no [`Rows`] created from it is still iterating).
+fn return_to_cache(
+    cache: &Option<Arc<Mutex<StatementCache>>>,
+    sql: &str,
+    stmt: &Arc<Mutex<turso_core::Statement>>,
+) {
+    if let Some(cache) = cache {
+        if Arc::strong_count(stmt) == 1 {
+            if let Ok(mut cache) = cache.lock() {
+                cache.push(sql.to_string(), Arc::clone(stmt));
+            }
+        }
+    }
+}
+
 impl Statement {
     /// Query the database with this prepared statement.
     pub async fn query(&mut self, params: impl IntoParams) -> Result<Rows> {
@@ -261,6 +454,8 @@ impl Statement {
         #[allow(clippy::arc_with_non_send_sync)]
         let rows = Rows {
             inner: Arc::clone(&self.inner),
+            sql: self.sql.clone(),
+            cache: self.cache.clone(),
         };
         Ok(rows)
     }
@@ -369,12 +564,16 @@ pub struct Transaction {}
 /// Results of a prepared statement query.
 pub struct Rows {
     inner: Arc<Mutex<turso_core::Statement>>,
+    sql: String,
+    cache: Option<Arc<Mutex<StatementCache>>>,
 }
 
 impl Clone for Rows {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            sql: self.sql.clone(),
+            cache: self.cache.clone(),
         }
     }
 }
@@ -382,6 +581,12 @@ impl Clone for Rows {
 unsafe impl Send for Rows {}
 unsafe impl Sync for Rows {}
 
+impl Drop for Rows {
+    fn drop(&mut self) {
+        return_to_cache(&self.cache, &self.sql, &self.inner);
+    }
+}
+
 impl Rows {
     /// Fetch the next row of this result set.
     pub async fn next(&mut self) -> Result<Option<Row>> {
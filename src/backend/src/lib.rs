@@ -71,8 +71,9 @@ fn custom_getrandom(buf: &mut [u8]) -> Result<(), getrandom::Error> {
 #[ic_cdk::query]
 async fn greet(name: String) -> String {
     let memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(1)));
-    let db = Builder::with_memory(memory).build().await.unwrap();
-    let conn = db.connect().unwrap();
+    let wal_memory = MEMORY_MANAGER.with_borrow(|m| m.get(MemoryId::new(2)));
+    let db = Builder::with_memory(memory, wal_memory).build().await.unwrap();
+    let conn = db.connect().await.unwrap();
 
     conn.execute("CREATE TABLE IF NOT EXISTS users (name TEXT)", ())
         .await
@@ -83,7 +84,7 @@ async fn greet(name: String) -> String {
         .unwrap();
 
     let mut stmt = conn
-        .prepare("SELECT * FROM users WHERE name = ?1")
+        .prepare_cached("SELECT * FROM users WHERE name = ?1")
         .await
         .unwrap();
 